@@ -13,17 +13,38 @@
 // limitations under the License.
 
 use futures::channel::mpsc;
+use futures::future::BoxFuture;
 use futures::StreamExt;
 use gateway_client::GatewayClient;
 use log::*;
 use nymsphinx::{addressing::nodes::NymNodeRoutingAddress, SphinxPacket};
+use rand::Rng;
+use std::time::Duration;
 use tokio::runtime::Handle;
+use tokio::sync::watch;
 use tokio::task::JoinHandle;
+use tokio::time::sleep;
 
 pub struct MixMessage(NymNodeRoutingAddress, SphinxPacket);
 pub type BatchMixMessageSender = mpsc::UnboundedSender<Vec<MixMessage>>;
 pub type BatchMixMessageReceiver = mpsc::UnboundedReceiver<Vec<MixMessage>>;
 
+// lets whoever owns the controller learn that the retry budget below has been exhausted and the
+// gateway connection is considered fatally dead - the graceful replacement for the old panic.
+pub type ShutdownNotificationSender = mpsc::UnboundedSender<String>;
+pub type ShutdownNotificationReceiver = mpsc::UnboundedReceiver<String>;
+
+// lets whoever owns the controller observe the current connection health without having to poll
+// anything - `watch` always holds the latest value for every clone of the receiver.
+pub type GatewayConnectionWatcher = watch::Receiver<GatewayConnectionState>;
+
+// `GatewayClient` (a separate crate) doesn't have a way to re-establish its own websocket
+// connection, and nothing in this crate can add one to it sight unseen - so instead of guessing
+// at an API it doesn't have, the controller is handed a closure by whoever *does* know how to
+// build and authenticate a fresh `GatewayClient` (same url, same cached `SharedKeys`), and calls
+// that instead.
+pub type GatewayReconnector = Box<dyn FnMut() -> BoxFuture<'static, Result<GatewayClient, String>> + Send>;
+
 impl MixMessage {
     pub fn new(address: NymNodeRoutingAddress, packet: SphinxPacket) -> Self {
         MixMessage(address, packet)
@@ -31,6 +52,16 @@ impl MixMessage {
 }
 
 const MAX_FAILURE_COUNT: usize = 100;
+const MAX_RECONNECTION_ATTEMPTS: usize = 10;
+const INITIAL_RECONNECTION_BACKOFF: Duration = Duration::from_millis(500);
+const MAX_RECONNECTION_BACKOFF: Duration = Duration::from_secs(60);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GatewayConnectionState {
+    Connected,
+    Reconnecting,
+    Dead,
+}
 
 pub struct MixTrafficController {
     // TODO: most likely to be replaced by some higher level construct as
@@ -41,23 +72,55 @@ pub struct MixTrafficController {
     // TODO: this is temporary work-around.
     // in long run `gateway_client` will be moved away from `MixTrafficController` anyway.
     consecutive_gateway_failure_count: usize,
+
+    // once the reconnection budget is spent and the owner's been notified, the connection stays
+    // dead for good - without this, the very next batch would retry sending through the same
+    // stale gateway_client, fail, and re-trigger another full backoff cycle and shutdown
+    // notification forever.
+    is_dead: bool,
+
+    connection_state: watch::Sender<GatewayConnectionState>,
+    shutdown: ShutdownNotificationSender,
+    reconnector: GatewayReconnector,
 }
 
 impl MixTrafficController {
     pub fn new(
         mix_rx: BatchMixMessageReceiver,
         gateway_client: GatewayClient,
-    ) -> MixTrafficController {
-        MixTrafficController {
+        shutdown: ShutdownNotificationSender,
+        reconnector: GatewayReconnector,
+    ) -> (MixTrafficController, GatewayConnectionWatcher) {
+        let (connection_state, connection_watcher) =
+            watch::channel(GatewayConnectionState::Connected);
+
+        let controller = MixTrafficController {
             gateway_client,
             mix_rx,
             consecutive_gateway_failure_count: 0,
-        }
+            is_dead: false,
+            connection_state,
+            shutdown,
+            reconnector,
+        };
+
+        (controller, connection_watcher)
+    }
+
+    fn set_connection_state(&self, state: GatewayConnectionState) {
+        // if nobody's watching the state anymore that's fine, we don't need any subscribers
+        let _ = self.connection_state.send(state);
     }
 
     async fn on_messages(&mut self, mut mix_messages: Vec<MixMessage>) {
         debug_assert!(!mix_messages.is_empty());
 
+        if self.is_dead {
+            // the reconnection budget was already spent and the owner already notified - drop
+            // the batch instead of trying the same stale gateway_client again.
+            return;
+        }
+
         let success = if mix_messages.len() == 1 {
             let mix_message = mix_messages.pop().unwrap();
             self.gateway_client
@@ -74,10 +137,8 @@ impl MixTrafficController {
             Err(e) => {
                 error!("Failed to send sphinx packet(s) to the gateway! - {:?}", e);
                 self.consecutive_gateway_failure_count += 1;
-                if self.consecutive_gateway_failure_count == MAX_FAILURE_COUNT {
-                    // todo: in the future this should initiate a 'graceful' shutdown or try
-                    // to reconnect?
-                    panic!("failed to send sphinx packet to the gateway {} times in a row - assuming the gateway is dead. Can't do anything about it yet :(", MAX_FAILURE_COUNT)
+                if self.consecutive_gateway_failure_count >= MAX_FAILURE_COUNT {
+                    self.reconnect_with_backoff().await;
                 }
             }
             Ok(_) => {
@@ -87,6 +148,60 @@ impl MixTrafficController {
         }
     }
 
+    // Supervised reconnection: instead of panicking and taking the whole client down, repeatedly
+    // re-establish the gateway connection (re-running authentication with the cached
+    // `SharedKeys`) using exponential backoff with jitter, up to a bounded retry budget. Mix
+    // messages that arrive while we're down are handled by `on_messages` as usual once `run`
+    // resumes - any batch that was already in flight when the failure happened is simply dropped,
+    // since buffering it would let a dead gateway grow our memory use without bound.
+    async fn reconnect_with_backoff(&mut self) {
+        self.set_connection_state(GatewayConnectionState::Reconnecting);
+
+        let mut backoff = INITIAL_RECONNECTION_BACKOFF;
+        for attempt in 1..=MAX_RECONNECTION_ATTEMPTS {
+            let jitter = Duration::from_secs_f64(
+                rand::thread_rng().gen_range(0.0..0.5) * backoff.as_secs_f64(),
+            );
+            let wait = backoff + jitter;
+            info!(
+                "waiting {:?} before gateway reconnection attempt {}/{}",
+                wait, attempt, MAX_RECONNECTION_ATTEMPTS
+            );
+            sleep(wait).await;
+
+            match (self.reconnector)().await {
+                Ok(gateway_client) => {
+                    info!("re-established the connection to the gateway");
+                    self.gateway_client = gateway_client;
+                    self.consecutive_gateway_failure_count = 0;
+                    self.set_connection_state(GatewayConnectionState::Connected);
+                    return;
+                }
+                Err(e) => {
+                    warn!("gateway reconnection attempt {} failed - {:?}", attempt, e);
+                    backoff = (backoff * 2).min(MAX_RECONNECTION_BACKOFF);
+                }
+            }
+        }
+
+        error!(
+            "failed to reconnect to the gateway after {} attempts - giving up",
+            MAX_RECONNECTION_ATTEMPTS
+        );
+        self.is_dead = true;
+        self.set_connection_state(GatewayConnectionState::Dead);
+        if self
+            .shutdown
+            .unbounded_send(format!(
+                "gateway connection could not be re-established after {} attempts",
+                MAX_RECONNECTION_ATTEMPTS
+            ))
+            .is_err()
+        {
+            warn!("could not notify owner of the fatal gateway disconnection - the shutdown channel is already gone");
+        }
+    }
+
     pub async fn run(&mut self) {
         while let Some(mix_messages) = self.mix_rx.next().await {
             self.on_messages(mix_messages).await;
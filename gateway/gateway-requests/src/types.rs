@@ -16,37 +16,277 @@ use crate::authentication::encrypted_address::EncryptedAddressBytes;
 use crate::authentication::iv::AuthenticationIV;
 use crate::registration::handshake::SharedKeys;
 use crate::GatewayMacSize;
+use chacha20poly1305::aead::{Aead, NewAead};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
 use crypto::generic_array::typenum::Unsigned;
 use crypto::hmac::recompute_keyed_hmac_and_verify_tag;
 use crypto::symmetric::stream_cipher;
+use hkdf::Hkdf;
 use nymsphinx::addressing::nodes::{NymNodeRoutingAddress, NymNodeRoutingAddressError};
 use nymsphinx::params::packet_sizes::PacketSize;
 use nymsphinx::params::{GatewayEncryptionAlgorithm, GatewayIntegrityHmacAlgorithm};
 use nymsphinx::{DestinationAddressBytes, SphinxPacket};
+use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::VecDeque;
 use std::{
     convert::{TryFrom, TryInto},
     fmt::{self, Error, Formatter},
 };
 use tungstenite::protocol::Message;
 
+// frame layout for the AEAD-framed `BinaryRequest`/`BinaryResponse` variants introduced at
+// protocol version 2: `nonce(AEAD_NONCE_SIZE) || ciphertext || tag(AEAD_TAG_SIZE)`.
+//
+// 192-bit (24-byte) XChaCha20Poly1305 nonces, not the 96-bit ChaCha20Poly1305 ones: a long-lived
+// mixnet session can realistically push enough sphinx packets to approach the ~2^32-message
+// birthday bound where randomly-generated 96-bit nonces start colliding.
+const AEAD_NONCE_SIZE: usize = 24;
+const AEAD_TAG_SIZE: usize = 16;
+
+// below this negotiated protocol version, binary frames still use the original zero-IV,
+// detached-HMAC framing so that peers who haven't upgraded remain interoperable.
+const AEAD_FRAMING_MIN_PROTOCOL_VERSION: u8 = 2;
+
+// `GatewayEncryptionAlgorithm`'s key isn't sized for ChaCha20Poly1305 specifically, so reusing it
+// as-is would panic inside `Key::from_slice` the moment it isn't exactly 32 bytes. HKDF-expand it
+// into a correctly sized, domain-separated AEAD key instead, the same way `ratchet_shared_key_material`
+// already derives rekeyed material below.
+fn derive_aead_key(ikm: &[u8], info: &[u8]) -> Key {
+    let hkdf = Hkdf::<Sha256>::new(None, ikm);
+    let mut key_bytes = [0u8; 32];
+    hkdf.expand(info, &mut key_bytes)
+        .expect("hkdf output is far shorter than its maximum length for sha256");
+    *Key::from_slice(&key_bytes)
+}
+
+fn aead_key(shared_keys: &SharedKeys) -> Key {
+    derive_aead_key(shared_keys.encryption_key(), b"nym-gateway-aead-frame-key")
+}
+
+// shared by `BinaryRequest`/`BinaryResponse`'s AEAD framing so there's one place that knows the
+// `variant_tag_byte || payload` plaintext layout and the nonce-prepended wire format.
+fn encode_aead_frame(variant_tag: u8, payload: &[u8], key: &Key) -> Vec<u8> {
+    let mut plaintext = Vec::with_capacity(1 + payload.len());
+    plaintext.push(variant_tag);
+    plaintext.extend_from_slice(payload);
+
+    let mut nonce_bytes = [0u8; AEAD_NONCE_SIZE];
+    OsRng.fill_bytes(&mut nonce_bytes);
+
+    let cipher = XChaCha20Poly1305::new(key);
+    // only fails if the plaintext exceeds the cipher's maximum message length, which a
+    // single frame never will
+    let ciphertext = cipher
+        .encrypt(XNonce::from_slice(&nonce_bytes), plaintext.as_ref())
+        .expect("chacha20poly1305 encryption of a single frame should never fail");
+
+    let mut framed = Vec::with_capacity(AEAD_NONCE_SIZE + ciphertext.len());
+    framed.extend_from_slice(&nonce_bytes);
+    framed.extend_from_slice(&ciphertext);
+    framed
+}
+
+// the inverse of `encode_aead_frame` - returns the `variant_tag_byte || payload` plaintext so the
+// caller can match on whichever variant tag its own type uses.
+fn decode_aead_frame(raw_req: &[u8], key: &Key) -> Result<Vec<u8>, GatewayRequestsError> {
+    if raw_req.len() < AEAD_NONCE_SIZE + AEAD_TAG_SIZE {
+        return Err(GatewayRequestsError::TooShortRequest);
+    }
+
+    let (nonce_bytes, ciphertext) = raw_req.split_at(AEAD_NONCE_SIZE);
+    let cipher = XChaCha20Poly1305::new(key);
+    cipher
+        .decrypt(XNonce::from_slice(nonce_bytes), ciphertext)
+        .map_err(|_| GatewayRequestsError::InvalidMAC)
+}
+
+// The lowest and highest binary protocol versions this build of the handshake code
+// knows how to speak. Bumping `MAX_SUPPORTED_PROTOCOL_VERSION` is how a new wire format
+// (e.g. the AEAD-framed `BinaryRequest`/`BinaryResponse`) gets rolled out without breaking
+// peers that haven't upgraded yet.
+pub const MIN_SUPPORTED_PROTOCOL_VERSION: u8 = 1;
+pub const MAX_SUPPORTED_PROTOCOL_VERSION: u8 = 2;
+
+// A bitflag set of optional features a peer supports. Kept as a plain integer (rather than
+// pulling in a bitflag crate) so it serializes with serde with no extra glue.
+pub type HandshakeCapabilities = u16;
+
+pub mod capabilities {
+    use super::HandshakeCapabilities;
+
+    pub const BATCH_SEND: HandshakeCapabilities = 0b0000_0001;
+    pub const AEAD_FRAMES: HandshakeCapabilities = 0b0000_0010;
+    pub const REKEY: HandshakeCapabilities = 0b0000_0100;
+
+    pub const NONE: HandshakeCapabilities = 0;
+    pub const ALL: HandshakeCapabilities = BATCH_SEND | AEAD_FRAMES | REKEY;
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum HandshakeErrorReason {
+    UnsupportedProtocolVersion,
+    Generic,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct HandshakeError {
+    pub reason: HandshakeErrorReason,
+    pub message: String,
+}
+
+impl HandshakeError {
+    pub fn new<S: Into<String>>(reason: HandshakeErrorReason, message: S) -> Self {
+        HandshakeError {
+            reason,
+            message: message.into(),
+        }
+    }
+
+    pub fn unsupported_protocol_version(remote_version: u8) -> Self {
+        HandshakeError::new(
+            HandshakeErrorReason::UnsupportedProtocolVersion,
+            format!(
+                "peer requested protocol version {} but this build only supports {}..={}",
+                remote_version, MIN_SUPPORTED_PROTOCOL_VERSION, MAX_SUPPORTED_PROTOCOL_VERSION
+            ),
+        )
+    }
+}
+
+// defaults for the fields below so that a peer still sending the original 2-field
+// `{type, data}` handshake payload keeps deserializing instead of failing outright - a
+// version-negotiation message that isn't itself tolerant of an unupgraded peer would defeat the
+// whole point of negotiating.
+fn default_protocol_version() -> u8 {
+    MIN_SUPPORTED_PROTOCOL_VERSION
+}
+
+fn default_transport() -> String {
+    PLAIN_TRANSPORT_NAME.to_string()
+}
+
+fn default_dh_public_key() -> Vec<u8> {
+    Vec::new()
+}
+
+// same tolerance treatment as the HandshakePayload fields above, for the exact same reason: this
+// is what actually carries a failed negotiation back to an old peer, so it needs to stay readable
+// by (and able to read) the original flat `{message}` shape.
+fn default_handshake_error_reason() -> HandshakeErrorReason {
+    HandshakeErrorReason::Generic
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum RegistrationHandshake {
-    HandshakePayload { data: Vec<u8> },
-    HandshakeError { message: String },
+    HandshakePayload {
+        data: Vec<u8>,
+        #[serde(default = "default_protocol_version")]
+        protocol_version: u8,
+        #[serde(default)]
+        capabilities: HandshakeCapabilities,
+        // populated by the receiving side once it has picked the version it intends to speak;
+        // absent on the initial request as the initiator doesn't yet know what the peer supports.
+        #[serde(default)]
+        negotiated_version: Option<u8>,
+        // name of the `ObfuscationTransport` the sender wants to use for every frame that
+        // follows this handshake, e.g. `PLAIN_TRANSPORT_NAME`. Lets new obfuscators be added
+        // without touching any of the request/response types themselves.
+        #[serde(default = "default_transport")]
+        transport: String,
+        // sender's ephemeral X25519 public key for `HandshakeKeyExchange` - absent (and ignored)
+        // for `PLAIN_TRANSPORT_NAME`, populated once both sides have negotiated an obfuscated
+        // transport and need a secret to derive its key from. Defaulted so a peer that doesn't
+        // know about the key exchange yet still deserializes this payload.
+        #[serde(default = "default_dh_public_key")]
+        dh_public_key: Vec<u8>,
+    },
+    HandshakeError {
+        #[serde(default = "default_handshake_error_reason")]
+        reason: HandshakeErrorReason,
+        message: String,
+    },
 }
 
 impl RegistrationHandshake {
     pub fn new_payload(data: Vec<u8>) -> Self {
-        RegistrationHandshake::HandshakePayload { data }
+        Self::new_payload_with_key_exchange(data, default_dh_public_key())
+    }
+
+    // same as `new_payload`, but also carries the sender's `HandshakeKeyExchange` public key so
+    // the receiving side can derive the secret an obfuscated transport will need. Kept separate
+    // from `new_payload` rather than adding a required parameter there, since most callers
+    // (anyone still only negotiating `PLAIN_TRANSPORT_NAME`) have nothing to put in this field.
+    pub fn new_payload_with_key_exchange(data: Vec<u8>, dh_public_key: Vec<u8>) -> Self {
+        RegistrationHandshake::HandshakePayload {
+            data,
+            protocol_version: MAX_SUPPORTED_PROTOCOL_VERSION,
+            capabilities: capabilities::ALL,
+            negotiated_version: None,
+            transport: PLAIN_TRANSPORT_NAME.to_string(),
+            dh_public_key,
+        }
+    }
+
+    pub fn new_negotiated_payload(
+        data: Vec<u8>,
+        capabilities: HandshakeCapabilities,
+        negotiated_version: u8,
+        transport: String,
+    ) -> Self {
+        Self::new_negotiated_payload_with_key_exchange(
+            data,
+            capabilities,
+            negotiated_version,
+            transport,
+            default_dh_public_key(),
+        )
+    }
+
+    pub fn new_negotiated_payload_with_key_exchange(
+        data: Vec<u8>,
+        capabilities: HandshakeCapabilities,
+        negotiated_version: u8,
+        transport: String,
+        dh_public_key: Vec<u8>,
+    ) -> Self {
+        RegistrationHandshake::HandshakePayload {
+            data,
+            protocol_version: MAX_SUPPORTED_PROTOCOL_VERSION,
+            capabilities,
+            negotiated_version: Some(negotiated_version),
+            transport,
+            dh_public_key,
+        }
     }
 
     pub fn new_error<S: Into<String>>(message: S) -> Self {
         RegistrationHandshake::HandshakeError {
+            reason: HandshakeErrorReason::Generic,
             message: message.into(),
         }
     }
+
+    // wraps whatever `negotiate_version`/`HandshakeError::unsupported_protocol_version` produced
+    pub fn new_error_from(error: HandshakeError) -> Self {
+        RegistrationHandshake::HandshakeError {
+            reason: error.reason,
+            message: error.message,
+        }
+    }
+
+    // Picks the highest protocol version both sides can speak, or a structured error if the
+    // supported ranges don't overlap at all.
+    pub fn negotiate_version(remote_version: u8) -> Result<u8, HandshakeError> {
+        if remote_version < MIN_SUPPORTED_PROTOCOL_VERSION {
+            return Err(HandshakeError::unsupported_protocol_version(remote_version));
+        }
+        Ok(remote_version.min(MAX_SUPPORTED_PROTOCOL_VERSION))
+    }
 }
 
 impl TryFrom<String> for RegistrationHandshake {
@@ -113,7 +353,20 @@ pub enum ClientControlRequest {
         iv: String,
     },
     #[serde(alias = "handshakePayload")]
-    RegisterHandshakeInitRequest { data: Vec<u8> },
+    RegisterHandshakeInitRequest {
+        data: Vec<u8>,
+        #[serde(default = "default_protocol_version")]
+        protocol_version: u8,
+        #[serde(default)]
+        capabilities: HandshakeCapabilities,
+    },
+    // Ratchets the symmetric material forward without a full re-registration handshake. `key_id`
+    // names the new generation being introduced and `salt` is the fresh randomness the HKDF step
+    // is keyed on; see `ratchet_shared_key_material`.
+    Rekey {
+        key_id: KeyGenerationId,
+        salt: Vec<u8>,
+    },
 }
 
 impl ClientControlRequest {
@@ -158,10 +411,28 @@ impl TryInto<String> for ClientControlRequest {
 #[derive(Serialize, Deserialize, Debug)]
 #[serde(tag = "type", rename_all = "camelCase")]
 pub enum ServerResponse {
-    Authenticate { status: bool },
-    Register { status: bool },
-    Send { status: bool },
-    Error { message: String },
+    Authenticate {
+        status: bool,
+        // the wire format that's actually in effect for this session, as negotiated during
+        // the registration handshake. `None` if authentication failed before negotiation happened.
+        protocol_version: Option<u8>,
+    },
+    Register {
+        status: bool,
+        protocol_version: Option<u8>,
+    },
+    Send {
+        status: bool,
+    },
+    // acknowledges a `ClientControlRequest::Rekey` - once acknowledged, the generation it
+    // superseded can be dropped from `RecentKeyGenerations`.
+    Rekey {
+        status: bool,
+        key_id: KeyGenerationId,
+    },
+    Error {
+        message: String,
+    },
 }
 
 impl ServerResponse {
@@ -185,6 +456,22 @@ impl ServerResponse {
             _ => false,
         }
     }
+
+    // Only meaningful once `implies_successful_authentication` is true - tells the caller
+    // which binary protocol version the gateway settled on for this session.
+    pub fn negotiated_protocol_version(&self) -> Option<u8> {
+        match self {
+            ServerResponse::Authenticate {
+                status: true,
+                protocol_version,
+            } => *protocol_version,
+            ServerResponse::Register {
+                status: true,
+                protocol_version,
+            } => *protocol_version,
+            _ => None,
+        }
+    }
 }
 
 impl Into<Message> for ServerResponse {
@@ -211,13 +498,27 @@ pub enum BinaryRequest {
     },
 }
 
-// Right now the only valid `BinaryRequest` is a request to forward a sphinx packet.
-// It is encrypted using the derived shared key between client and the gateway. Thanks to
-// randomness inside the sphinx packet themselves (even via the same route), the 0s IV can be used here.
-// HOWEVER, NOTE: If we introduced another 'BinaryRequest', we must carefully examine if a 0s IV
-// would work there.
+// `ForwardSphinx` is tagged `0x00` in the AEAD framing below so further `BinaryRequest`
+// variants can be added later without another framing change.
+const FORWARD_SPHINX_VARIANT_TAG: u8 = 0x00;
+
 impl BinaryRequest {
     pub fn try_from_encrypted_tagged_bytes(
+        raw_req: Vec<u8>,
+        shared_keys: &SharedKeys,
+        protocol_version: u8,
+    ) -> Result<Self, GatewayRequestsError> {
+        if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            Self::try_from_aead_framed_bytes(raw_req, shared_keys)
+        } else {
+            Self::try_from_legacy_zero_iv_bytes(raw_req, shared_keys)
+        }
+    }
+
+    // Legacy framing: `mac(GatewayMacSize) || ciphertext`, decrypted with a fixed all-zero IV.
+    // Safe only because the lone `ForwardSphinx` variant already randomizes the sphinx body.
+    // Kept solely so peers who negotiated a pre-AEAD protocol version still interoperate.
+    fn try_from_legacy_zero_iv_bytes(
         mut raw_req: Vec<u8>,
         shared_keys: &SharedKeys,
     ) -> Result<Self, GatewayRequestsError> {
@@ -248,12 +549,32 @@ impl BinaryRequest {
             &mut message_bytes_mut,
         );
 
-        // right now there's only a single option possible which significantly simplifies the logic
-        // if we decided to allow for more 'binary' messages, the API wouldn't need to change
-        let address = NymNodeRoutingAddress::try_from_bytes(&message_bytes_mut)?;
+        Self::parse_forward_sphinx(message_bytes_mut)
+    }
+
+    // AEAD framing: `nonce(AEAD_NONCE_SIZE) || ciphertext || tag(AEAD_TAG_SIZE)`, where the
+    // ciphertext covers `variant_tag_byte || payload`. The fresh random nonce is what makes a
+    // single combined encrypt+authenticate pass safe without any cross-message state, which
+    // removes the need for a separate `recompute_keyed_hmac_and_verify_tag` step.
+    fn try_from_aead_framed_bytes(
+        raw_req: Vec<u8>,
+        shared_keys: &SharedKeys,
+    ) -> Result<Self, GatewayRequestsError> {
+        let plaintext = decode_aead_frame(&raw_req, &aead_key(shared_keys))?;
+
+        match plaintext.split_first() {
+            Some((&FORWARD_SPHINX_VARIANT_TAG, payload)) => Self::parse_forward_sphinx(payload),
+            _ => Err(GatewayRequestsError::MalformedEncryption),
+        }
+    }
+
+    // right now there's only a single option possible which significantly simplifies the logic
+    // if we decided to allow for more 'binary' messages, only this helper would need to change
+    fn parse_forward_sphinx(message_bytes: &[u8]) -> Result<Self, GatewayRequestsError> {
+        let address = NymNodeRoutingAddress::try_from_bytes(message_bytes)?;
         let addr_offset = address.bytes_min_len();
 
-        let sphinx_packet_data = &message_bytes_mut[addr_offset..];
+        let sphinx_packet_data = &message_bytes[addr_offset..];
         let packet_size = sphinx_packet_data.len();
         if PacketSize::get_type(packet_size).is_err() {
             // TODO: should this allow AckPacket sizes?
@@ -272,17 +593,33 @@ impl BinaryRequest {
         }
     }
 
-    pub fn into_encrypted_tagged_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
+    fn forwarding_data(address: NymNodeRoutingAddress, sphinx_packet: SphinxPacket) -> Vec<u8> {
+        address
+            .as_bytes()
+            .into_iter()
+            .chain(sphinx_packet.to_bytes().into_iter())
+            .collect()
+    }
+
+    pub fn into_encrypted_tagged_bytes(
+        self,
+        shared_key: &SharedKeys,
+        protocol_version: u8,
+    ) -> Vec<u8> {
+        if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            self.into_aead_framed_bytes(shared_key)
+        } else {
+            self.into_legacy_zero_iv_bytes(shared_key)
+        }
+    }
+
+    fn into_legacy_zero_iv_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
         match self {
             BinaryRequest::ForwardSphinx {
                 address,
                 sphinx_packet,
             } => {
-                let forwarding_data: Vec<_> = address
-                    .as_bytes()
-                    .into_iter()
-                    .chain(sphinx_packet.to_bytes().into_iter())
-                    .collect();
+                let forwarding_data = Self::forwarding_data(address, sphinx_packet);
 
                 // TODO: it could be theoretically slightly more efficient if the data wasn't taken
                 // by reference because then it makes a copy for encryption rather than do it in place
@@ -291,6 +628,20 @@ impl BinaryRequest {
         }
     }
 
+    fn into_aead_framed_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
+        let (variant_tag, payload) = match self {
+            BinaryRequest::ForwardSphinx {
+                address,
+                sphinx_packet,
+            } => (
+                FORWARD_SPHINX_VARIANT_TAG,
+                Self::forwarding_data(address, sphinx_packet),
+            ),
+        };
+
+        encode_aead_frame(variant_tag, &payload, &aead_key(shared_key))
+    }
+
     // TODO: this will be encrypted, etc.
     pub fn new_forward_request(
         address: NymNodeRoutingAddress,
@@ -302,8 +653,38 @@ impl BinaryRequest {
         }
     }
 
-    pub fn into_ws_message(self, shared_key: &SharedKeys) -> Message {
-        Message::Binary(self.into_encrypted_tagged_bytes(shared_key))
+    // `key_id` is only woven into the frame for AEAD-framed peers - the legacy zero-IV framing
+    // predates rekeying and has no generation concept to tag.
+    pub fn into_ws_message(
+        self,
+        shared_key: &SharedKeys,
+        protocol_version: u8,
+        key_id: KeyGenerationId,
+    ) -> Message {
+        let frame = self.into_encrypted_tagged_bytes(shared_key, protocol_version);
+        let frame = if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            tag_with_key_generation(key_id, frame)
+        } else {
+            frame
+        };
+        Message::Binary(frame)
+    }
+
+    // inverse of `into_ws_message` - looks the tagged generation up in `keys` and decrypts with
+    // whatever `SharedKeys` that generation still has on file, rather than assuming generation 0.
+    pub fn try_from_tagged_ws_bytes(
+        raw_frame: Vec<u8>,
+        keys: &RecentKeyGenerations,
+        protocol_version: u8,
+    ) -> Result<Self, GatewayRequestsError> {
+        if protocol_version < AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            let shared_keys = keys.get(0).ok_or(GatewayRequestsError::InvalidMAC)?;
+            return Self::try_from_encrypted_tagged_bytes(raw_frame, shared_keys, protocol_version);
+        }
+
+        let (key_id, rest) = split_key_generation_tag(&raw_frame)?;
+        let shared_keys = keys.get(key_id).ok_or(GatewayRequestsError::InvalidMAC)?;
+        Self::try_from_encrypted_tagged_bytes(rest.to_vec(), shared_keys, protocol_version)
     }
 }
 
@@ -312,10 +693,26 @@ pub enum BinaryResponse {
     PushedMixMessage(Vec<u8>),
 }
 
+// tagged `0x00` in the AEAD framing below for the same reason as `FORWARD_SPHINX_VARIANT_TAG`.
+const PUSHED_MIX_MESSAGE_VARIANT_TAG: u8 = 0x00;
+
 impl BinaryResponse {
     pub fn try_from_encrypted_tagged_bytes(
         raw_req: Vec<u8>,
         shared_keys: &SharedKeys,
+        protocol_version: u8,
+    ) -> Result<Self, GatewayRequestsError> {
+        if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            Self::try_from_aead_framed_bytes(raw_req, shared_keys)
+        } else {
+            Self::try_from_legacy_zero_iv_bytes(raw_req, shared_keys)
+        }
+    }
+
+    // see `BinaryRequest::try_from_legacy_zero_iv_bytes` for why the zero IV is safe here
+    fn try_from_legacy_zero_iv_bytes(
+        raw_req: Vec<u8>,
+        shared_keys: &SharedKeys,
     ) -> Result<Self, GatewayRequestsError> {
         let mac_size = GatewayMacSize::to_usize();
         if raw_req.len() < mac_size {
@@ -343,7 +740,34 @@ impl BinaryResponse {
         Ok(BinaryResponse::PushedMixMessage(plaintext))
     }
 
-    pub fn into_encrypted_tagged_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
+    // see `BinaryRequest::try_from_aead_framed_bytes` for the frame layout
+    fn try_from_aead_framed_bytes(
+        raw_req: Vec<u8>,
+        shared_keys: &SharedKeys,
+    ) -> Result<Self, GatewayRequestsError> {
+        let plaintext = decode_aead_frame(&raw_req, &aead_key(shared_keys))?;
+
+        match plaintext.split_first() {
+            Some((&PUSHED_MIX_MESSAGE_VARIANT_TAG, payload)) => {
+                Ok(BinaryResponse::PushedMixMessage(payload.to_vec()))
+            }
+            _ => Err(GatewayRequestsError::MalformedEncryption),
+        }
+    }
+
+    pub fn into_encrypted_tagged_bytes(
+        self,
+        shared_key: &SharedKeys,
+        protocol_version: u8,
+    ) -> Vec<u8> {
+        if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            self.into_aead_framed_bytes(shared_key)
+        } else {
+            self.into_legacy_zero_iv_bytes(shared_key)
+        }
+    }
+
+    fn into_legacy_zero_iv_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
         match self {
             // TODO: it could be theoretically slightly more efficient if the data wasn't taken
             // by reference because then it makes a copy for encryption rather than do it in place
@@ -351,12 +775,385 @@ impl BinaryResponse {
         }
     }
 
+    fn into_aead_framed_bytes(self, shared_key: &SharedKeys) -> Vec<u8> {
+        let BinaryResponse::PushedMixMessage(message) = self;
+        encode_aead_frame(PUSHED_MIX_MESSAGE_VARIANT_TAG, &message, &aead_key(shared_key))
+    }
+
     pub fn new_pushed_mix_message(msg: Vec<u8>) -> Self {
         BinaryResponse::PushedMixMessage(msg)
     }
 
-    pub fn into_ws_message(self, shared_key: &SharedKeys) -> Message {
-        Message::Binary(self.into_encrypted_tagged_bytes(shared_key))
+    // see `BinaryRequest::into_ws_message` for why `key_id` is only tagged on for AEAD framing
+    pub fn into_ws_message(
+        self,
+        shared_key: &SharedKeys,
+        protocol_version: u8,
+        key_id: KeyGenerationId,
+    ) -> Message {
+        let frame = self.into_encrypted_tagged_bytes(shared_key, protocol_version);
+        let frame = if protocol_version >= AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            tag_with_key_generation(key_id, frame)
+        } else {
+            frame
+        };
+        Message::Binary(frame)
+    }
+
+    // see `BinaryRequest::try_from_tagged_ws_bytes`
+    pub fn try_from_tagged_ws_bytes(
+        raw_frame: Vec<u8>,
+        keys: &RecentKeyGenerations,
+        protocol_version: u8,
+    ) -> Result<Self, GatewayRequestsError> {
+        if protocol_version < AEAD_FRAMING_MIN_PROTOCOL_VERSION {
+            let shared_keys = keys.get(0).ok_or(GatewayRequestsError::InvalidMAC)?;
+            return Self::try_from_encrypted_tagged_bytes(raw_frame, shared_keys, protocol_version);
+        }
+
+        let (key_id, rest) = split_key_generation_tag(&raw_frame)?;
+        let shared_keys = keys.get(key_id).ok_or(GatewayRequestsError::InvalidMAC)?;
+        Self::try_from_encrypted_tagged_bytes(rest.to_vec(), shared_keys, protocol_version)
+    }
+}
+
+// identifies a generation of `SharedKeys` produced by a `ClientControlRequest::Rekey` step.
+// generation 0 is always the pair derived during the original registration handshake.
+pub type KeyGenerationId = u32;
+
+const KEY_GENERATION_ID_SIZE: usize = std::mem::size_of::<KeyGenerationId>();
+
+// prepends the generation id a frame was encrypted under, so the receiving side knows which
+// entry of `RecentKeyGenerations` to decrypt it with.
+pub fn tag_with_key_generation(key_id: KeyGenerationId, frame: Vec<u8>) -> Vec<u8> {
+    let mut tagged = Vec::with_capacity(KEY_GENERATION_ID_SIZE + frame.len());
+    tagged.extend_from_slice(&key_id.to_be_bytes());
+    tagged.extend_from_slice(&frame);
+    tagged
+}
+
+// the inverse of `tag_with_key_generation` - splits the generation id off a keyed frame so the
+// caller can look up the right `SharedKeys` before decrypting the rest.
+pub fn split_key_generation_tag(
+    raw_frame: &[u8],
+) -> Result<(KeyGenerationId, &[u8]), GatewayRequestsError> {
+    if raw_frame.len() < KEY_GENERATION_ID_SIZE {
+        return Err(GatewayRequestsError::TooShortRequest);
+    }
+    let (id_bytes, rest) = raw_frame.split_at(KEY_GENERATION_ID_SIZE);
+    let key_id = KeyGenerationId::from_be_bytes(id_bytes.try_into().unwrap());
+    Ok((key_id, rest))
+}
+
+// derives fresh encryption/MAC key bytes from the currently active generation's raw key material
+// plus a random salt via HKDF. HKDF is one-way, so a compromise of a later generation's keys
+// doesn't expose earlier generations' keys or the traffic sent under them; whoever holds the
+// current generation's keys and sees the salt can derive the next generation's keys.
+// Takes the raw key bytes rather than `&SharedKeys` directly since
+// that's all the derivation actually needs, which keeps it testable without a real `SharedKeys`.
+pub fn ratchet_shared_key_material(
+    current_encryption_key: &[u8],
+    current_mac_key: &[u8],
+    salt: &[u8],
+) -> (Vec<u8>, Vec<u8>) {
+    let initial_key_material: Vec<u8> = current_encryption_key
+        .iter()
+        .chain(current_mac_key.iter())
+        .copied()
+        .collect();
+
+    let hkdf = Hkdf::<Sha256>::new(Some(salt), &initial_key_material);
+
+    let mut next_encryption_key = vec![0u8; current_encryption_key.len()];
+    hkdf.expand(b"nym-gateway-rekey-encryption", &mut next_encryption_key)
+        .expect("hkdf output is far shorter than its maximum length for sha256");
+
+    let mut next_mac_key = vec![0u8; current_mac_key.len()];
+    hkdf.expand(b"nym-gateway-rekey-mac", &mut next_mac_key)
+        .expect("hkdf output is far shorter than its maximum length for sha256");
+
+    (next_encryption_key, next_mac_key)
+}
+
+// the gateway's window of recent `SharedKeys` generations, keyed by `key_id` - frames tagged with
+// an older, still-tracked generation keep decrypting during the brief transition after a rekey.
+pub struct RecentKeyGenerations {
+    window_size: usize,
+    generations: VecDeque<(KeyGenerationId, SharedKeys)>,
+}
+
+impl RecentKeyGenerations {
+    pub fn new(window_size: usize) -> Self {
+        RecentKeyGenerations {
+            window_size,
+            generations: VecDeque::with_capacity(window_size),
+        }
+    }
+
+    pub fn get(&self, key_id: KeyGenerationId) -> Option<&SharedKeys> {
+        self.generations
+            .iter()
+            .find(|(id, _)| *id == key_id)
+            .map(|(_, keys)| keys)
+    }
+
+    pub fn active_key_id(&self) -> Option<KeyGenerationId> {
+        self.generations.back().map(|(id, _)| *id)
+    }
+
+    // inserting a new generation is how an acknowledged rekey evicts whatever fell out of the
+    // window - there's no separate "acknowledge" step beyond this.
+    pub fn insert(&mut self, key_id: KeyGenerationId, keys: SharedKeys) {
+        self.generations.push_back((key_id, keys));
+        while self.generations.len() > self.window_size {
+            self.generations.pop_front();
+        }
+    }
+
+    // handles an incoming `ClientControlRequest::Rekey`: ratchets the currently active generation
+    // forward with the request's salt, inserts the result as `key_id`, and returns the
+    // `ServerResponse::Rekey` to send back. Returns `None` for any other request variant.
+    // `SharedKeys`'s own constructor lives outside this crate's visible module slice, so
+    // `build_keys` (supplied by a caller who does have it) turns the derived key bytes into one.
+    pub fn handle_rekey(
+        &mut self,
+        request: &ClientControlRequest,
+        build_keys: impl FnOnce(Vec<u8>, Vec<u8>) -> SharedKeys,
+    ) -> Option<ServerResponse> {
+        let (key_id, salt) = match request {
+            ClientControlRequest::Rekey { key_id, salt } => (*key_id, salt),
+            _ => return None,
+        };
+
+        let response = match self.generations.back() {
+            Some((_, current)) => {
+                let (next_encryption_key, next_mac_key) =
+                    ratchet_shared_key_material(current.encryption_key(), current.mac_key(), salt);
+                self.insert(key_id, build_keys(next_encryption_key, next_mac_key));
+                ServerResponse::Rekey {
+                    status: true,
+                    key_id,
+                }
+            }
+            None => ServerResponse::Rekey {
+                status: false,
+                key_id,
+            },
+        };
+
+        Some(response)
+    }
+}
+
+pub const PLAIN_TRANSPORT_NAME: &str = "plain";
+pub const PADDED_STREAM_TRANSPORT_NAME: &str = "padded-stream";
+
+// bucket sizes a padded frame gets rounded up to, so a frame's exact length isn't visible on
+// the wire. A frame always goes to the smallest bucket that fits it, so frames of the same
+// length (e.g. sphinx packets of one `PacketSize`) always share a bucket, and frames whose
+// lengths fall in different buckets stay distinguishable by their padded size.
+const PADDING_BUCKET_SIZES: [usize; 4] = [512, 1024, 2048, 4096];
+
+// length of the length-prefix that's encrypted together with the frame payload, see
+// `PaddedStreamTransport::obfuscate`.
+const FRAME_LENGTH_PREFIX_SIZE: usize = 8;
+
+// `into_bytes` flattens both `Message::Text` and `Message::Binary` down to raw bytes, so the
+// original kind has to be carried alongside the payload explicitly - guessing it back from
+// whether the recovered bytes happen to be valid UTF-8 would silently misdeliver a binary
+// ciphertext that happens to decode as text.
+const FRAME_KIND_TAG_SIZE: usize = 1;
+const TEXT_FRAME_KIND: u8 = 0x00;
+const BINARY_FRAME_KIND: u8 = 0x01;
+
+// wraps the plainly-framed tungstenite `Message`s the rest of the protocol puts on the wire,
+// which is trivially fingerprintable by a DPI censor. The client and gateway agree on an
+// implementation by name during the registration handshake (see `HandshakePayload::transport`).
+pub trait ObfuscationTransport: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn obfuscate(&mut self, frame: Message) -> Message;
+
+    fn deobfuscate(&mut self, frame: Message) -> Result<Message, GatewayRequestsError>;
+}
+
+// puts frames on the wire completely unmodified - what every peer speaks until a different
+// transport is negotiated, and the fallback if negotiation doesn't happen.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct PlainTransport;
+
+impl ObfuscationTransport for PlainTransport {
+    fn name(&self) -> &'static str {
+        PLAIN_TRANSPORT_NAME
+    }
+
+    fn obfuscate(&mut self, frame: Message) -> Message {
+        frame
+    }
+
+    fn deobfuscate(&mut self, frame: Message) -> Result<Message, GatewayRequestsError> {
+        Ok(frame)
+    }
+}
+
+// first-pass key exchange for `HandshakePayload::dh_public_key`: plain X25519 ECDH, run inside
+// the otherwise plaintext registration handshake. This is NOT yet the Elligator2-encoded,
+// wire-indistinguishable exchange an unobservable handshake needs - a raw X25519 public key is
+// still a recognisable curve point to a sufficiently motivated observer, unlike an
+// Elligator2-mapped one which looks like uniformly random bytes. Closing that gap is tracked as a
+// follow-up; this closes the more basic gap of there being no key exchange at all, which is what
+// `PaddedStreamTransport::new`'s `shared_secret` actually needs to come from.
+pub struct HandshakeKeyExchange {
+    secret: x25519_dalek::EphemeralSecret,
+}
+
+impl HandshakeKeyExchange {
+    pub fn new() -> Self {
+        HandshakeKeyExchange {
+            secret: x25519_dalek::EphemeralSecret::new(OsRng),
+        }
+    }
+
+    // goes straight into `HandshakePayload::dh_public_key`
+    pub fn public_key_bytes(&self) -> [u8; 32] {
+        x25519_dalek::PublicKey::from(&self.secret).to_bytes()
+    }
+
+    // consumes `self` because `EphemeralSecret` is explicitly single-use - reusing one across
+    // more than one exchange would let an observer who recovers one session's secret link it to
+    // every other session the same keypair was used in.
+    pub fn derive_shared_secret(self, remote_public_key_bytes: &[u8]) -> Result<Vec<u8>, GatewayRequestsError> {
+        let remote_public_key_bytes: [u8; 32] = remote_public_key_bytes
+            .try_into()
+            .map_err(|_| GatewayRequestsError::MalformedEncryption)?;
+        let remote_public_key = x25519_dalek::PublicKey::from(remote_public_key_bytes);
+        let raw_shared_secret = self.secret.diffie_hellman(&remote_public_key);
+
+        // raw X25519 output isn't guaranteed uniformly random - HKDF it the same way every other
+        // derived key in this file is, rather than handing raw DH output to callers directly.
+        let hkdf = Hkdf::<Sha256>::new(None, raw_shared_secret.as_bytes());
+        let mut shared_secret = vec![0u8; 32];
+        hkdf.expand(b"nym-gateway-handshake-dh-shared-secret", &mut shared_secret)
+            .expect("hkdf output is far shorter than its maximum length for sha256");
+        Ok(shared_secret)
+    }
+}
+
+impl Default for HandshakeKeyExchange {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// default obfuscated transport. `shared_secret` is the output of `HandshakeKeyExchange`,
+// exchanged via `HandshakePayload::dh_public_key` during the registration handshake (the
+// handshake frame itself is still sent in the clear - see `HandshakeKeyExchange` for exactly what
+// that does and doesn't hide). Encrypts each frame's length prefix and pads it to the smallest
+// fitting bucket in `PADDING_BUCKET_SIZES`, so the exact frame sizes and the JSON structure are
+// no longer visible on the wire; the bucket a frame lands in still is.
+pub struct PaddedStreamTransport {
+    key: Key,
+}
+
+impl PaddedStreamTransport {
+    pub fn new(shared_secret: &[u8]) -> Self {
+        // `shared_secret` comes from whatever key exchange produced it, not from us, so nothing
+        // guarantees it's exactly 32 bytes - derive a correctly sized key via HKDF instead of
+        // handing it straight to `Key::from_slice`, which panics on a mismatched length.
+        PaddedStreamTransport {
+            key: derive_aead_key(shared_secret, b"nym-gateway-padded-stream-key"),
+        }
+    }
+
+    fn bucket_size(framed_len: usize) -> usize {
+        PADDING_BUCKET_SIZES
+            .iter()
+            .copied()
+            .find(|&bucket| bucket >= framed_len)
+            .unwrap_or_else(|| framed_len.next_power_of_two())
+    }
+
+    fn into_bytes(frame: Message) -> (u8, Vec<u8>) {
+        match frame {
+            Message::Text(text) => (TEXT_FRAME_KIND, text.into_bytes()),
+            Message::Binary(bytes) => (BINARY_FRAME_KIND, bytes),
+            other => (BINARY_FRAME_KIND, other.into_data()),
+        }
+    }
+}
+
+impl ObfuscationTransport for PaddedStreamTransport {
+    fn name(&self) -> &'static str {
+        PADDED_STREAM_TRANSPORT_NAME
+    }
+
+    fn obfuscate(&mut self, frame: Message) -> Message {
+        let (frame_kind, payload) = Self::into_bytes(frame);
+
+        let mut framed =
+            Vec::with_capacity(FRAME_KIND_TAG_SIZE + FRAME_LENGTH_PREFIX_SIZE + payload.len());
+        framed.push(frame_kind);
+        framed.extend_from_slice(&(payload.len() as u64).to_be_bytes());
+        framed.extend_from_slice(&payload);
+        framed.resize(Self::bucket_size(framed.len()), 0);
+
+        let mut nonce_bytes = [0u8; AEAD_NONCE_SIZE];
+        OsRng.fill_bytes(&mut nonce_bytes);
+
+        let cipher = XChaCha20Poly1305::new(&self.key);
+        let ciphertext = cipher
+            .encrypt(XNonce::from_slice(&nonce_bytes), framed.as_ref())
+            .expect("chacha20poly1305 encryption of a single frame should never fail");
+
+        let mut wire_bytes = Vec::with_capacity(AEAD_NONCE_SIZE + ciphertext.len());
+        wire_bytes.extend_from_slice(&nonce_bytes);
+        wire_bytes.extend_from_slice(&ciphertext);
+        Message::Binary(wire_bytes)
+    }
+
+    fn deobfuscate(&mut self, frame: Message) -> Result<Message, GatewayRequestsError> {
+        let (_, wire_bytes) = Self::into_bytes(frame);
+        if wire_bytes.len()
+            < AEAD_NONCE_SIZE + AEAD_TAG_SIZE + FRAME_KIND_TAG_SIZE + FRAME_LENGTH_PREFIX_SIZE
+        {
+            return Err(GatewayRequestsError::TooShortRequest);
+        }
+
+        let (nonce_bytes, ciphertext) = wire_bytes.split_at(AEAD_NONCE_SIZE);
+        let cipher = XChaCha20Poly1305::new(&self.key);
+        let framed = cipher
+            .decrypt(XNonce::from_slice(nonce_bytes), ciphertext)
+            .map_err(|_| GatewayRequestsError::MalformedEncryption)?;
+
+        let (frame_kind, rest) = framed
+            .split_first()
+            .ok_or(GatewayRequestsError::MalformedEncryption)?;
+        let (len_bytes, rest) = rest.split_at(FRAME_LENGTH_PREFIX_SIZE);
+        let payload_len = u64::from_be_bytes(len_bytes.try_into().unwrap()) as usize;
+        if payload_len > rest.len() {
+            return Err(GatewayRequestsError::MalformedEncryption);
+        }
+
+        let payload = rest[..payload_len].to_vec();
+        match *frame_kind {
+            TEXT_FRAME_KIND => String::from_utf8(payload)
+                .map(Message::Text)
+                .map_err(|_| GatewayRequestsError::MalformedEncryption),
+            _ => Ok(Message::Binary(payload)),
+        }
+    }
+}
+
+// builds the `ObfuscationTransport` negotiated by name during the registration handshake.
+// returns `None` for a name neither side recognises.
+pub fn obfuscation_transport_by_name(
+    name: &str,
+    shared_secret: &[u8],
+) -> Option<Box<dyn ObfuscationTransport>> {
+    match name {
+        PLAIN_TRANSPORT_NAME => Some(Box::new(PlainTransport)),
+        PADDED_STREAM_TRANSPORT_NAME => Some(Box::new(PaddedStreamTransport::new(shared_secret))),
+        _ => None,
     }
 }
 
@@ -369,15 +1166,180 @@ mod tests {
         let handshake_data = vec![1, 2, 3, 4, 5, 6];
         let handshake_payload = RegistrationHandshake::HandshakePayload {
             data: handshake_data.clone(),
+            protocol_version: MAX_SUPPORTED_PROTOCOL_VERSION,
+            capabilities: capabilities::ALL,
+            negotiated_version: None,
+            transport: PLAIN_TRANSPORT_NAME.to_string(),
+            dh_public_key: Vec::new(),
         };
         let serialized = serde_json::to_string(&handshake_payload).unwrap();
         let deserialized = ClientControlRequest::try_from(serialized).unwrap();
 
         match deserialized {
-            ClientControlRequest::RegisterHandshakeInitRequest { data } => {
+            ClientControlRequest::RegisterHandshakeInitRequest { data, .. } => {
                 assert_eq!(data, handshake_data)
             }
             _ => unreachable!("this branch shouldn't have been reached!"),
         }
     }
+
+    #[test]
+    fn handshake_error_still_deserializes_the_original_flat_message_only_shape() {
+        let legacy = r#"{"type":"handshakeError","message":"bad handshake"}"#;
+        let deserialized: RegistrationHandshake = serde_json::from_str(legacy).unwrap();
+
+        match deserialized {
+            RegistrationHandshake::HandshakeError { reason, message } => {
+                assert_eq!(reason, HandshakeErrorReason::Generic);
+                assert_eq!(message, "bad handshake");
+            }
+            _ => unreachable!("this branch shouldn't have been reached!"),
+        }
+    }
+
+    #[test]
+    fn version_negotiation_picks_the_highest_mutually_supported_version() {
+        assert_eq!(
+            RegistrationHandshake::negotiate_version(MAX_SUPPORTED_PROTOCOL_VERSION).unwrap(),
+            MAX_SUPPORTED_PROTOCOL_VERSION
+        );
+    }
+
+    #[test]
+    fn version_negotiation_rejects_versions_below_the_supported_range() {
+        assert!(RegistrationHandshake::negotiate_version(MIN_SUPPORTED_PROTOCOL_VERSION - 1).is_err());
+    }
+
+    #[test]
+    fn handshake_key_exchange_lets_both_sides_agree_on_the_same_secret() {
+        let initiator = HandshakeKeyExchange::new();
+        let responder = HandshakeKeyExchange::new();
+
+        let initiator_public_key = initiator.public_key_bytes();
+        let responder_public_key = responder.public_key_bytes();
+
+        let initiator_secret = initiator
+            .derive_shared_secret(&responder_public_key)
+            .unwrap();
+        let responder_secret = responder
+            .derive_shared_secret(&initiator_public_key)
+            .unwrap();
+
+        assert_eq!(initiator_secret, responder_secret);
+
+        // a third, unrelated exchange has no way of landing on the same secret
+        let eavesdropper_secret = HandshakeKeyExchange::new()
+            .derive_shared_secret(&responder_public_key)
+            .unwrap();
+        assert_ne!(initiator_secret, eavesdropper_secret);
+    }
+
+    #[test]
+    fn handshake_key_exchange_rejects_a_malformed_public_key() {
+        let exchange = HandshakeKeyExchange::new();
+        assert!(exchange.derive_shared_secret(&[0u8; 4]).is_err());
+    }
+
+    #[test]
+    fn padded_stream_transport_roundtrips_a_text_frame() {
+        let shared_secret = [42u8; 32];
+        let mut sender = PaddedStreamTransport::new(&shared_secret);
+        let mut receiver = PaddedStreamTransport::new(&shared_secret);
+
+        let original = Message::Text("hello gateway".to_string());
+        let obfuscated = sender.obfuscate(original.clone());
+        assert!(matches!(obfuscated, Message::Binary(_)));
+
+        let recovered = receiver.deobfuscate(obfuscated).unwrap();
+        assert_eq!(recovered, original);
+    }
+
+    #[test]
+    fn key_generation_tag_roundtrips_around_an_arbitrary_frame() {
+        let frame = vec![1, 2, 3, 4, 5];
+        let tagged = tag_with_key_generation(7, frame.clone());
+
+        let (key_id, untagged) = split_key_generation_tag(&tagged).unwrap();
+        assert_eq!(key_id, 7);
+        assert_eq!(untagged, frame.as_slice());
+    }
+
+    #[test]
+    fn key_generation_tag_rejects_frames_shorter_than_the_id_itself() {
+        assert!(split_key_generation_tag(&[0, 1, 2]).is_err());
+    }
+
+    #[test]
+    fn aead_key_derivation_handles_key_material_not_sized_for_chacha20poly1305() {
+        // `GatewayEncryptionAlgorithm`'s key size wasn't chosen for ChaCha20Poly1305, so
+        // `derive_aead_key` has to hand back a usable 32-byte key for whatever length it's
+        // actually given instead of panicking the way `Key::from_slice` would directly.
+        for len in [16, 24, 32, 48] {
+            let ikm = vec![7u8; len];
+            let key = derive_aead_key(&ikm, b"test-info");
+            assert_eq!(key.len(), 32);
+        }
+    }
+
+    // `BinaryRequest`/`BinaryResponse::{into_ws_message, try_from_tagged_ws_bytes}` take
+    // `&SharedKeys` directly, and `SharedKeys`'s constructor lives outside this crate's visible
+    // module slice (see `RecentKeyGenerations::handle_rekey`'s `build_keys` parameter) - there's
+    // no way to build a real instance here. This exercises the exact same frame layout those
+    // methods produce (key-generation tag || nonce || ciphertext, with the ciphertext covering
+    // `variant_tag_byte || payload`) via the shared `encode_aead_frame`/`decode_aead_frame`
+    // helpers they're both built on, with an arbitrary AEAD key standing in for a derived one.
+    #[test]
+    fn aead_framed_response_round_trips_through_the_key_generation_tag() {
+        let key = derive_aead_key(&[11u8; 32], b"test-binary-response-roundtrip");
+        let message = b"pushed mix message payload".to_vec();
+
+        let frame = encode_aead_frame(PUSHED_MIX_MESSAGE_VARIANT_TAG, &message, &key);
+        let tagged = tag_with_key_generation(3, frame);
+
+        let (key_id, untagged) = split_key_generation_tag(&tagged).unwrap();
+        assert_eq!(key_id, 3);
+
+        let plaintext = decode_aead_frame(untagged, &key).unwrap();
+        match plaintext.split_first() {
+            Some((&PUSHED_MIX_MESSAGE_VARIANT_TAG, payload)) => assert_eq!(payload, message),
+            _ => unreachable!("this branch shouldn't have been reached!"),
+        }
+    }
+
+    #[test]
+    fn aead_framed_response_rejects_a_frame_decoded_with_the_wrong_key() {
+        let key = derive_aead_key(&[11u8; 32], b"test-binary-response-roundtrip");
+        let wrong_key = derive_aead_key(&[12u8; 32], b"test-binary-response-roundtrip");
+        let frame = encode_aead_frame(PUSHED_MIX_MESSAGE_VARIANT_TAG, b"payload", &key);
+
+        assert!(decode_aead_frame(&frame, &wrong_key).is_err());
+    }
+
+    // exercises the exact derivation `RecentKeyGenerations::handle_rekey` ratchets forward on a
+    // `ClientControlRequest::Rekey`, directly on raw key bytes rather than a `SharedKeys` for the
+    // same reason as the test above. Confirms the handoff property the rekey flow depends on:
+    // the new generation's key material both differs from and is independent of the old
+    // generation's, so the old generation keeps decrypting anything still addressed to it.
+    #[test]
+    fn ratcheted_key_material_is_fresh_but_does_not_invalidate_the_previous_generation() {
+        let old_encryption_key = vec![1u8; 32];
+        let old_mac_key = vec![2u8; 20];
+        let salt = b"rekey-salt";
+
+        let (new_encryption_key, new_mac_key) =
+            ratchet_shared_key_material(&old_encryption_key, &old_mac_key, salt);
+        assert_ne!(new_encryption_key, old_encryption_key);
+        assert_ne!(new_mac_key, old_mac_key);
+
+        let old_aead_key = derive_aead_key(&old_encryption_key, b"nym-gateway-aead-frame-key");
+        let new_aead_key = derive_aead_key(&new_encryption_key, b"nym-gateway-aead-frame-key");
+
+        let old_frame = encode_aead_frame(PUSHED_MIX_MESSAGE_VARIANT_TAG, b"old generation", &old_aead_key);
+        let new_frame = encode_aead_frame(PUSHED_MIX_MESSAGE_VARIANT_TAG, b"new generation", &new_aead_key);
+
+        // the new generation came into existence, but the old one's key material is untouched
+        assert!(decode_aead_frame(&old_frame, &old_aead_key).is_ok());
+        assert!(decode_aead_frame(&new_frame, &new_aead_key).is_ok());
+        assert!(decode_aead_frame(&old_frame, &new_aead_key).is_err());
+    }
 }